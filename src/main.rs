@@ -4,25 +4,33 @@ extern crate walkdir;
 extern crate blake2;
 extern crate byteorder;
 extern crate unbytify;
+extern crate rayon;
+extern crate regex;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use std::collections::{HashMap, HashSet};
 use blake2::{Blake2b, Digest};
-use walkdir::{WalkDir, DirEntry};
+use walkdir::{WalkDir, DirEntry, DirEntryExt};
+use rayon::prelude::*;
+use regex::RegexSet;
 
 use std::ops::Deref;
 
 use std::collections::hash_map::Entry::Occupied;
 
-use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 use std::fmt;
 use std::fs;
 use std::ffi::OsStr;
 
 use std::os::unix::ffi::OsStrExt;
 
-use byteorder::{LittleEndian, WriteBytesExt};
+use std::os::unix::fs::MetadataExt;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
 
 
 #[derive(PartialEq ,Eq, Hash, Clone)]
@@ -38,7 +46,7 @@ struct DirectoryData {
 
 impl fmt::Debug for FileHash {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let &FileHash(ref slice) = self;
+        let FileHash(slice) = self;
         write!(f, "FileHash {:?}", &slice[..5])
     }
 }
@@ -55,16 +63,383 @@ impl DirectoryData {
     fn hash(&self) -> FileHash {
         let mut digest = Blake2b::default();
         for hash in &self.children_hashes {
-            digest.input(&hash)
+            digest.input(&hash[..])
         }
         FileHash(digest.result().to_vec())
     }
 }
 
-fn crawl_directory(root: PathBuf, map: &mut HashMap<FileHash, Vec<Rc<DirectoryData>>>, inodes : &mut HashSet<u64>) -> Rc<DirectoryData> {
+type DirMap = Mutex<HashMap<FileHash, Vec<Arc<DirectoryData>>>>;
+
+/// Taille du préfixe lu lors du premier passage du mode contenu, avant de se
+/// résoudre à relire le fichier entier en cas de collision.
+const CONTENT_PREFIX_LEN: usize = 4096;
+
+/// Façon de dériver le `FileHash` d'un fichier.
+///
+/// En mode `Metadata` (le défaut) on se contente du nom et de `st_size`, ce qui
+/// est rapide mais confond deux fichiers différents de même nom et même taille.
+/// En mode `Content` on dispose d'une clef de contenu précalculée par taille
+/// (cf. `compute_content_hashes`) que l'on combine au nom du fichier.
+enum HashMode {
+    Metadata,
+    Content(HashMap<PathBuf, Vec<u8>>),
+}
+
+/// Façon de compter la taille d'un fichier.
+///
+/// `Apparent` additionne `metadata.len()` (la taille logique), `Allocated` additionne
+/// `st_blocks * 512`, ce qui reflète l'espace réellement libéré en supprimant les
+/// doublons (fichiers creux, allocation par blocs).
+#[derive(Clone, Copy)]
+enum SizeMode {
+    Apparent,
+    Allocated,
+}
+
+/// Options de parcours partagées par `crawl_directory` et `collect_sizes`.
+#[derive(Clone, Copy)]
+struct ScanOptions {
+    follow_symlinks: bool,
+    size_mode: SizeMode,
+}
+
+fn file_size(metadata: &fs::Metadata, size_mode: SizeMode) -> u64 {
+    match size_mode {
+        SizeMode::Apparent => metadata.len(),
+        SizeMode::Allocated => metadata.blocks() * 512,
+    }
+}
+
+/// Filtre d'inclusion/exclusion appliqué à chaque fichier avant qu'il ne participe au scan.
+///
+/// Les extensions littérales (`--ext png,jpg`) sont comparées par appartenance exacte à un
+/// `HashSet`, les motifs `--include`/`--exclude` sont des globs (`*`, `?`) traduits en un
+/// `RegexSet` ancré. Un fichier est retenu s'il n'est pas exclu et, lorsqu'un filtre
+/// d'inclusion est présent, s'il correspond à une extension ou à un motif d'inclusion.
+///
+/// Les globs sont ancrés sur le chemin complet, donc `--exclude '*.png'` ne filtre que les
+/// chemins se terminant par `.png` ; utilisez `--exclude '*png*'` pour un test de sous-chaîne.
+struct Filter {
+    ext: Option<HashSet<String>>,
+    include: Option<RegexSet>,
+    exclude: Option<RegexSet>,
+}
+
+/// Traduit un glob shell (`*`, `?`) en regex ancrée, en échappant les métacaractères regex
+/// pour que `*.png` devienne `^.*\.png$` plutôt qu'une regex invalide qui ferait planter la
+/// compilation.
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::with_capacity(glob.len() + 2);
+    re.push('^');
+    for c in glob.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            // Métacaractères regex pris littéralement dans un glob.
+            '.' | '+' | '(' | ')' | '|' | '[' | ']' | '{' | '}' | '^' | '$' | '\\' => {
+                re.push('\\');
+                re.push(c);
+            }
+            _ => re.push(c),
+        }
+    }
+    re.push('$');
+    re
+}
+
+fn compile_patterns(value: Option<&str>) -> Option<RegexSet> {
+    value.map(|raw| {
+        let patterns: Vec<String> = raw
+            .split(',')
+            .map(|p| p.trim())
+            .filter(|p| !p.is_empty())
+            .map(glob_to_regex)
+            .collect();
+        RegexSet::new(&patterns).unwrap_or_else(|e| {
+            eprintln!("invalid pattern in {:?}: {}", raw, e);
+            std::process::exit(1);
+        })
+    })
+}
+
+impl Filter {
+    fn from_args(include: Option<&str>, exclude: Option<&str>, ext: Option<&str>) -> Filter {
+        let ext = ext.map(|raw| {
+            raw.split(',')
+                .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+                .filter(|e| !e.is_empty())
+                .collect::<HashSet<String>>()
+        });
+        Filter {
+            ext,
+            include: compile_patterns(include),
+            exclude: compile_patterns(exclude),
+        }
+    }
+
+    fn ext_matches(&self, path: &Path) -> bool {
+        match (self.ext.as_ref(), path.extension().and_then(|e| e.to_str())) {
+            (Some(extensions), Some(ext)) => extensions.contains(&ext.to_lowercase()),
+            _ => false,
+        }
+    }
+
+    fn accepts(&self, path: &Path) -> bool {
+        let as_text = path.to_string_lossy();
+        if let Some(ref exclude) = self.exclude {
+            if exclude.is_match(&as_text) {
+                return false;
+            }
+        }
+        // Sans filtre d'inclusion, tout ce qui n'est pas exclu passe.
+        if self.ext.is_none() && self.include.is_none() {
+            return true;
+        }
+        if self.ext_matches(path) {
+            return true;
+        }
+        match self.include {
+            Some(ref include) => include.is_match(&as_text),
+            None => false,
+        }
+    }
+}
+
+/// Première passe du mode contenu : recense tous les fichiers réguliers du sous-arbre
+/// par taille, sans lire le moindre octet.
+fn collect_sizes(root: &PathBuf, buckets: &mut HashMap<u64, Vec<PathBuf>>, filter: &Filter, opts: &ScanOptions) {
+    for entry in WalkDir::new(root).follow_links(opts.follow_symlinks) {
+        match entry {
+            Ok(e) => {
+                if e.file_type().is_file() && filter.accepts(e.path()) {
+                    match e.metadata() {
+                        Ok(meta) => buckets.entry(meta.len()).or_default().push(e.path().to_path_buf()),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+fn blake2_of(bytes: &[u8]) -> Vec<u8> {
+    let mut digest = Blake2b::default();
+    digest.input(bytes);
+    digest.result().to_vec()
+}
+
+fn read_prefix(path: &Path, len: usize) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+    let mut file = fs::File::open(path)?;
+    let mut buf = vec![0u8; len];
+    let read = file.read(&mut buf)?;
+    buf.truncate(read);
+    Ok(buf)
+}
+
+fn size_key(size: u64) -> Vec<u8> {
+    let mut wtr = vec![];
+    wtr.write_u64::<LittleEndian>(size).expect("failed to encode size as bytes");
+    wtr
+}
+
+/// Deuxième passe du mode contenu : pour chaque groupe de fichiers de même taille,
+/// décide d'une clef de contenu. Les tailles uniques gardent une clef synthétique
+/// (la taille seule, aucune lecture) ; les tailles partagées sont d'abord départagées
+/// sur un préfixe, et seules les collisions de préfixe déclenchent une lecture complète.
+fn compute_content_hashes(buckets: HashMap<u64, Vec<PathBuf>>) -> HashMap<PathBuf, Vec<u8>> {
+    let mut result = HashMap::new();
+    for (size, paths) in buckets {
+        if paths.len() == 1 {
+            result.insert(paths.into_iter().next().unwrap(), size_key(size));
+            continue;
+        }
+
+        // On hache d'abord un préfixe pour éviter de relire entièrement des fichiers
+        // qui diffèrent dès les premiers octets.
+        let prefixes: Vec<(PathBuf, Vec<u8>)> = paths.par_iter().map(|path| {
+            let prefix = read_prefix(path, CONTENT_PREFIX_LEN).unwrap_or_else(|e| {
+                eprintln!("failed to read prefix at path {:?}: {}", path, e);
+                Vec::new()
+            });
+            (path.clone(), blake2_of(&prefix))
+        }).collect();
+
+        let mut by_prefix: HashMap<Vec<u8>, Vec<PathBuf>> = HashMap::new();
+        for (path, prefix_hash) in prefixes {
+            by_prefix.entry(prefix_hash).or_default().push(path);
+        }
+
+        for (prefix_hash, group) in by_prefix {
+            if group.len() == 1 {
+                result.insert(group.into_iter().next().unwrap(), prefix_hash);
+            } else {
+                let fulls: Vec<(PathBuf, Vec<u8>)> = group.par_iter().map(|path| {
+                    let bytes = fs::read(path).unwrap_or_else(|e| {
+                        eprintln!("failed to read content at path {:?}: {}", path, e);
+                        Vec::new()
+                    });
+                    (path.clone(), blake2_of(&bytes))
+                }).collect();
+                for (path, full_hash) in fulls {
+                    result.insert(path, full_hash);
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Entrée du cache sur disque : `(taille, mtime_sec, mtime_nsec, hash)`.
+type CacheEntry = (u64, i64, u32, Vec<u8>);
+
+/// Cache persistant des `FileHash` par chemin, invalidé sur la taille et la mtime.
+///
+/// On reprend l'invariant des caches de mtime façon dirstate : une entrée dont la
+/// mtime tombe sur la seconde où le scan a démarré est « ambiguë » (un fichier modifié
+/// plus tard dans la même seconde serait indiscernable), on la rehache donc toujours
+/// et on ne la mémorise pas.
+///
+/// Le cache est propre au mode de hachage. Le `FileHash` d'un fichier dépend du mode :
+/// en mode métadonnées il ne dépend que du nom et de la taille, donc `(path, size, mtime)`
+/// le détermine et la réutilisation est valide. En mode contenu il dépend de la composition
+/// globale des seaux de taille (une taille unique à un scan peut devenir une collision au
+/// suivant), de sorte qu'une entrée n'est pas réutilisable d'un scan à l'autre : le cache y
+/// est donc désactivé, et le fichier sur disque est de toute façon distinct pour ne pas
+/// polluer le mode métadonnées.
+struct HashCache {
+    entries: Mutex<HashMap<PathBuf, CacheEntry>>,
+    // Chemins rencontrés pendant ce scan : les entrées chargées du disque mais jamais
+    // revues (fichiers supprimés/renommés) sont élaguées avant sauvegarde.
+    seen: Mutex<HashSet<PathBuf>>,
+    scan_start_sec: i64,
+    content_mode: bool,
+}
+
+fn cache_file_path(content_mode: bool) -> Option<PathBuf> {
+    let file = if content_mode { "content-cache" } else { "metadata-cache" };
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")));
+    base.map(|base| base.join("dupdirfinder").join(file))
+}
+
+fn serialize_cache(entries: &HashMap<PathBuf, CacheEntry>) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.write_u64::<LittleEndian>(entries.len() as u64).expect("failed to encode cache length");
+    for (path, &(size, mtime_sec, mtime_nsec, ref hash)) in entries {
+        let path_bytes = path.as_os_str().as_bytes();
+        out.write_u64::<LittleEndian>(path_bytes.len() as u64).unwrap();
+        out.extend_from_slice(path_bytes);
+        out.write_u64::<LittleEndian>(size).unwrap();
+        out.write_i64::<LittleEndian>(mtime_sec).unwrap();
+        out.write_u32::<LittleEndian>(mtime_nsec).unwrap();
+        out.write_u64::<LittleEndian>(hash.len() as u64).unwrap();
+        out.extend_from_slice(hash);
+    }
+    out
+}
+
+fn deserialize_cache(bytes: &[u8]) -> Option<HashMap<PathBuf, CacheEntry>> {
+    fn take<'a>(bytes: &'a [u8], pos: &mut usize, n: usize) -> Option<&'a [u8]> {
+        if *pos + n > bytes.len() {
+            return None;
+        }
+        let slice = &bytes[*pos..*pos + n];
+        *pos += n;
+        Some(slice)
+    }
+
+    let mut pos = 0;
+    let count = LittleEndian::read_u64(take(bytes, &mut pos, 8)?);
+    let mut map = HashMap::with_capacity(count as usize);
+    for _ in 0..count {
+        let path_len = LittleEndian::read_u64(take(bytes, &mut pos, 8)?) as usize;
+        let path = PathBuf::from(OsStr::from_bytes(take(bytes, &mut pos, path_len)?));
+        let size = LittleEndian::read_u64(take(bytes, &mut pos, 8)?);
+        let mtime_sec = LittleEndian::read_i64(take(bytes, &mut pos, 8)?);
+        let mtime_nsec = LittleEndian::read_u32(take(bytes, &mut pos, 4)?);
+        let hash_len = LittleEndian::read_u64(take(bytes, &mut pos, 8)?) as usize;
+        let hash = take(bytes, &mut pos, hash_len)?.to_vec();
+        map.insert(path, (size, mtime_sec, mtime_nsec, hash));
+    }
+    Some(map)
+}
+
+impl HashCache {
+    fn load(scan_start_sec: i64, content_mode: bool) -> HashCache {
+        let entries = if content_mode {
+            HashMap::new()
+        } else {
+            cache_file_path(content_mode)
+                .and_then(|path| fs::read(path).ok())
+                .and_then(|bytes| deserialize_cache(&bytes))
+                .unwrap_or_default()
+        };
+        HashCache { entries: Mutex::new(entries), seen: Mutex::new(HashSet::new()), scan_start_sec, content_mode }
+    }
+
+    fn lookup(&self, path: &Path, size: u64, mtime_sec: i64, mtime_nsec: u32) -> Option<FileHash> {
+        if self.content_mode {
+            return None; // le hash contenu n'est pas réutilisable d'un scan à l'autre
+        }
+        // Tout fichier visité est « revu », qu'on touche ou non le cache : cela préserve
+        // son entrée lors de l'élagage de fin de scan.
+        self.seen.lock().unwrap().insert(path.to_path_buf());
+        if mtime_sec == self.scan_start_sec {
+            return None; // mtime ambiguë : on rehache toujours
+        }
+        match self.entries.lock().unwrap().get(path) {
+            Some(&(c_size, c_sec, c_nsec, ref hash))
+                if c_size == size && c_sec == mtime_sec && c_nsec == mtime_nsec =>
+            {
+                Some(FileHash(hash.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    fn store(&self, path: &Path, size: u64, mtime_sec: i64, mtime_nsec: u32, hash: &FileHash) {
+        if self.content_mode {
+            return; // on ne persiste pas d'entrée dépendante de la composition
+        }
+        if mtime_sec == self.scan_start_sec {
+            return; // mtime ambiguë : on ne mémorise pas l'entrée
+        }
+        self.entries.lock().unwrap().insert(path.to_path_buf(), (size, mtime_sec, mtime_nsec, hash.0.clone()));
+    }
+
+    fn save(&self) {
+        if self.content_mode {
+            return; // rien à persister en mode contenu
+        }
+        let path = match cache_file_path(self.content_mode) {
+            Some(path) => path,
+            None => return,
+        };
+        if let Some(parent) = path.parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                eprintln!("failed to create cache directory {:?}: {}", parent, e);
+                return;
+            }
+        }
+        // Élague les entrées jamais revues ce scan pour borner la croissance du cache.
+        let mut entries = self.entries.lock().unwrap();
+        let seen = self.seen.lock().unwrap();
+        entries.retain(|path, _| seen.contains(path));
+        let bytes = serialize_cache(&entries);
+        if let Err(e) = fs::write(&path, bytes) {
+            eprintln!("failed to write cache {:?}: {}", path, e);
+        }
+    }
+}
+
+fn crawl_directory(root: PathBuf, map: &DirMap, inodes: &Mutex<HashSet<u64>>, mode: &HashMode, cache: &HashCache, filter: &Filter, opts: &ScanOptions) -> Arc<DirectoryData> {
     let mut subdir_paths = Vec::new();
     let mut files_paths = Vec::new();
-    for dir_entry_result in WalkDir::new(&root).follow_links(false).max_depth(1) {
+    for dir_entry_result in WalkDir::new(&root).follow_links(opts.follow_symlinks).max_depth(1) {
         match dir_entry_result {
             Ok(dir_entry) => {
                 let path = dir_entry.path().to_path_buf();
@@ -72,12 +447,13 @@ fn crawl_directory(root: PathBuf, map: &mut HashMap<FileHash, Vec<Rc<DirectoryDa
                     continue;
                 }
                 if dir_entry.file_type().is_file() {
-                    files_paths.push(path);
-                } else if dir_entry.file_type().is_dir() {
-                    if check_inode(inodes, &dir_entry) {
+                    if filter.accepts(&path) {
+                        files_paths.push(path);
+                    }
+                } else if dir_entry.file_type().is_dir()
+                    && check_inode(inodes, &dir_entry) {
                         subdir_paths.push(path);
                     }
-                }
             }
             Err(e) => {
                 eprintln!("{}", e);
@@ -85,36 +461,56 @@ fn crawl_directory(root: PathBuf, map: &mut HashMap<FileHash, Vec<Rc<DirectoryDa
         }
     }
 
+    let files_count = files_paths.len() as u64;
+
+    // Descend dans les sous-répertoires en parallèle : chaque worker rend son propre
+    // Arc<DirectoryData>, qu'on replie ensuite dans le parent.
+    let subdir_results: Vec<Arc<DirectoryData>> = subdir_paths
+        .into_par_iter()
+        .map(|dir_path| crawl_directory(dir_path, map, inodes, mode, cache, filter, opts))
+        .collect();
+
+    // Hache les fichiers du répertoire courant en parallèle.
+    let file_results: Vec<(FileHash, u64)> = files_paths
+        .into_par_iter()
+        .map(|file_path| {
+            let hash = hash_file_metadata(&file_path, mode, cache);
+            let metadata = fs::metadata(&file_path).unwrap_or_else(|_| panic!("impossible to access metadata at path: {:?}", file_path));
+            // Chaque fichier compte sa taille dans le répertoire qui le contient. On ne
+            // déduplique pas les inodes ici : le faire globalement rendait `disk_size`
+            // dépendant de l'ordre de parcours parallèle (le premier répertoire atteint
+            // « réservait » l'inode, les autres comptaient 0), si bien que deux répertoires
+            // pourtant identiques affichaient des tailles divergentes. Le revers est que
+            // `disk_size` sur-estime l'espace pour les fichiers à inode partagé (liens durs
+            // ou symboliques avec `--follow-symlinks`) — cf. la note sur `space_wasted`.
+            (hash, file_size(&metadata, opts.size_mode))
+        })
+        .collect();
+
     let mut dir_data = DirectoryData {
         path: root,
         children_hashes: Vec::new(),
-        descendant_number: files_paths.len() as u64,
+        descendant_number: files_count,
         disk_size: 0,
     };
 
-    for dir_path in subdir_paths {
-        let subdir_data = crawl_directory(dir_path, map, inodes);
-        let hash = subdir_data.hash();
-        dir_data.children_hashes.push(hash);
+    for subdir_data in &subdir_results {
+        dir_data.children_hashes.push(subdir_data.hash());
         dir_data.descendant_number += 1 + subdir_data.descendant_number;
         dir_data.disk_size += subdir_data.disk_size;
     }
 
-    for file_path in files_paths {
-        let hash = hash_file_metadata(&file_path);
+    for (hash, size) in file_results {
         dir_data.children_hashes.push(hash);
-        let size = fs::metadata(&file_path).expect(&format!("impossible to access metadata at path: {:?}", file_path)).len();
         dir_data.disk_size += size;
     }
 
-    let rc_dir_data = Rc::new(dir_data);
-    let map_entry = map.entry(rc_dir_data.hash()).or_insert(Vec::new());
-
-    map_entry.push(rc_dir_data.clone());
+    let rc_dir_data = Arc::new(dir_data);
+    map.lock().unwrap().entry(rc_dir_data.hash()).or_default().push(rc_dir_data.clone());
     rc_dir_data
 }
 
-fn list_duplicates(map: HashMap<FileHash, Vec<Rc<DirectoryData>>>, min_size :u64) -> Vec<Vec<Rc<DirectoryData>>> {
+fn list_duplicates(map: HashMap<FileHash, Vec<Arc<DirectoryData>>>, min_size :u64) -> Vec<Vec<Arc<DirectoryData>>> {
     let mut result = vec![];
 
     let mut already_found_hashes : HashMap<FileHash, usize> = HashMap::new();
@@ -122,8 +518,8 @@ fn list_duplicates(map: HashMap<FileHash, Vec<Rc<DirectoryData>>>, min_size :u64
     let mut vect_of_key_and_entries  = map.into_iter().collect::<Vec<_>>();
 
     vect_of_key_and_entries.sort_unstable_by(|a, b| {
-        let first_element_of_a = a.1.get(0).expect("empty vector that should not be empty at line 119"); // the vectors are never empty
-        let first_element_of_b = b.1.get(0).expect("empty vector that should not be empty at line 120"); // the vectors are never empty
+        let first_element_of_a = a.1.first().expect("empty vector that should not be empty at line 521"); // the vectors are never empty
+        let first_element_of_b = b.1.first().expect("empty vector that should not be empty at line 522"); // the vectors are never empty
         first_element_of_b.descendant_number.cmp(&first_element_of_a.descendant_number)
     });
 
@@ -147,9 +543,9 @@ fn list_duplicates(map: HashMap<FileHash, Vec<Rc<DirectoryData>>>, min_size :u64
         // ou bien apparaissent dans le set en quantité inférieure à leur nombre d'occurence, ce qui veut dire qu'il existe un doublons en dehors des dossiers déjà traités
         // on les ajoute à la liste des résultats, et on met tous leurs enfants dans le set
         {
-            let first_dir_data = value.get(0).expect("empty vector that should not be empty at line 144");
+            let first_dir_data = value.first().expect("empty vector that should not be empty at line 546");
             for children_hash in &(first_dir_data.children_hashes) {
-                let mut entry = already_found_hashes.entry(children_hash.clone());
+                let entry = already_found_hashes.entry(children_hash.clone());
                 let num = entry.or_insert(0);
                 *num += value.len();
             }
@@ -167,7 +563,20 @@ fn list_duplicates(map: HashMap<FileHash, Vec<Rc<DirectoryData>>>, min_size :u64
     result
 }
 
-fn hash_file_metadata(path: &PathBuf) -> FileHash {
+fn hash_file_metadata(path: &PathBuf, mode: &HashMode, cache: &HashCache) -> FileHash {
+    // On relit la taille et la mtime pour interroger le cache avant tout calcul.
+    let (size, mtime_sec, mtime_nsec) = match fs::metadata(path) {
+        Ok(meta) => (meta.len(), meta.mtime(), meta.mtime_nsec() as u32),
+        Err(e) => {
+            eprintln!("impossible to access metadata at path {:?}: {}", path, e);
+            (0, 0, 0)
+        }
+    };
+
+    if let Some(cached) = cache.lookup(path, size, mtime_sec, mtime_nsec) {
+        return cached;
+    }
+
     //hash file name
     let file_name = path.file_name();
     let mut digest = Blake2b::default();
@@ -176,20 +585,37 @@ fn hash_file_metadata(path: &PathBuf) -> FileHash {
         OsStr::new("no file name")
     }).as_bytes());
 
-    let size = fs::metadata(path).expect(&format!("impossible to access metadata at path: {:?}", path)).len();
-    let mut wtr = vec![];
-    wtr.write_u64::<LittleEndian>(size).expect(&format!("failed to transform size {:?} to &[u8] at path: {:?}", size, path));
-    digest.input(&wtr);
+    match *mode {
+        HashMode::Metadata => {
+            digest.input(size_key(size));
+        }
+        // La clef de contenu a été calculée lors de la première passe ; en son
+        // absence (ne devrait pas arriver) on retombe sur la taille seule.
+        // La taille est mêlée à la clef : deux fichiers de tailles différentes dont les
+        // préfixes coïncident ne doivent pas se confondre.
+        HashMode::Content(ref keys) => match keys.get(path) {
+            Some(key) => {
+                digest.input(size_key(size));
+                digest.input(key)
+            }
+            None => {
+                eprintln!("No content hash for path: {:?}", path);
+                digest.input(size_key(size));
+            }
+        },
+    }
 
-    FileHash(digest.result().to_vec())
+    let hash = FileHash(digest.result().to_vec());
+    cache.store(path, size, mtime_sec, mtime_nsec, &hash);
+    hash
 }
 
 #[cfg(unix)]
-fn check_inode(set: &mut HashSet<u64>, entry: &DirEntry) -> bool {
-    set.insert(entry.ino())
+fn check_inode(set: &Mutex<HashSet<u64>>, entry: &DirEntry) -> bool {
+    set.lock().unwrap().insert(entry.ino())
 }
 #[cfg(not(unix))]
-fn check_inode(_: &mut HashSet<u64>, _: &DirEntry) -> bool {
+fn check_inode(_: &Mutex<HashSet<u64>>, _: &DirEntry) -> bool {
     true
 }
 
@@ -198,6 +624,145 @@ fn validate_byte_size(s: String) -> Result<(), String> {
         |_| format!("{:?} is not a byte size", s))
 }
 
+/// Construit le `HashMode` pour un ensemble de racines. En mode contenu, la première
+/// passe recense les fichiers par taille sur toutes les racines fournies.
+fn build_mode(roots: &[PathBuf], content_mode: bool, filter: &Filter, opts: &ScanOptions) -> HashMode {
+    if content_mode {
+        let mut buckets = HashMap::new();
+        for root in roots {
+            collect_sizes(root, &mut buckets, filter, opts);
+        }
+        HashMode::Content(compute_content_hashes(buckets))
+    } else {
+        HashMode::Metadata
+    }
+}
+
+/// Format de sortie demandé sur la ligne de commande.
+#[derive(Clone, Copy)]
+enum OutputFormat {
+    Human,
+    Json,
+    Ndjson,
+}
+
+impl OutputFormat {
+    fn is_human(&self) -> bool {
+        matches!(*self, OutputFormat::Human)
+    }
+}
+
+fn json_escape_str(s: &str, out: &mut String) {
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Sérialise un chemin en chaîne JSON. Les fragments UTF-8 valides sont échappés
+/// normalement ; les octets invalides (chemins non-UTF-8, courants sous Unix) sont
+/// encodés à la façon `surrogateescape` de Python, chacun comme l'échappement
+/// `\u{:04x}` du point de code `0xDC00 + octet`. Le résultat est sans perte : un
+/// consommateur qui retraduit les demi-codets bas isolés en octets retrouve le chemin
+/// d'origine.
+fn json_path(path: &Path) -> String {
+    let mut bytes = path.as_os_str().as_bytes();
+    let mut out = String::from("\"");
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(s) => {
+                json_escape_str(s, &mut out);
+                break;
+            }
+            Err(e) => {
+                let valid = e.valid_up_to();
+                // SAFETY : `valid_up_to` garantit un préfixe UTF-8 valide.
+                json_escape_str(unsafe { std::str::from_utf8_unchecked(&bytes[..valid]) }, &mut out);
+                match e.error_len() {
+                    Some(len) => {
+                        for &b in &bytes[valid..valid + len] {
+                            out.push_str(&format!("\\u{:04x}", 0xDC00 + b as u32));
+                        }
+                        bytes = &bytes[valid + len..];
+                    }
+                    None => {
+                        for &b in &bytes[valid..] {
+                            out.push_str(&format!("\\u{:04x}", 0xDC00 + b as u32));
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn group_json(group: &[Arc<DirectoryData>]) -> String {
+    let disk_size = group[0].disk_size;
+    let descendant_number = group[0].descendant_number;
+    // Espace récupérable si l'on ne garde qu'une copie du groupe. C'est un majorant :
+    // `disk_size` compte chaque fichier, donc si des copies partagent des inodes (liens durs,
+    // ou symlinks suivis avec `--follow-symlinks`) supprimer les doublons ne libérera pas
+    // autant d'octets que cette estimation.
+    let space_wasted = (group.len() - 1) as u64 * disk_size;
+
+    let mut out = String::new();
+    out.push_str("{\"disk_size\":");
+    out.push_str(&disk_size.to_string());
+    out.push_str(",\"space_wasted\":");
+    out.push_str(&space_wasted.to_string());
+    out.push_str(",\"descendant_number\":");
+    out.push_str(&descendant_number.to_string());
+    out.push_str(",\"paths\":[");
+    for (i, dir) in group.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str(&json_path(&dir.path));
+    }
+    out.push_str("]}");
+    out
+}
+
+fn print_duplicates(duplicates: Vec<Vec<Arc<DirectoryData>>>, format: OutputFormat) {
+    match format {
+        OutputFormat::Human => {
+            for duplicate in duplicates {
+                println!("Duplicat de {:?} répertoires", duplicate.len());
+                let duplicate_size = duplicate.first().expect("yet another expectation not met").disk_size;
+                // Majorant : sur-estime l'espace récupérable quand des copies partagent des
+                // inodes (liens durs / symlinks suivis). Cf. `group_json`.
+                let space_wasted = (duplicate.len() - 1 ) as u64 * duplicate_size;
+                let (val, suffix) = unbytify::bytify(space_wasted);
+                println!("    Space wasted {:.1} {}", val, suffix);
+                for dir in duplicate {
+                    println!("{:?}", dir.path);
+                }
+
+                println!();
+            }
+        }
+        OutputFormat::Json => {
+            let groups: Vec<String> = duplicates.iter().map(|group| group_json(group)).collect();
+            println!("[{}]", groups.join(","));
+        }
+        OutputFormat::Ndjson => {
+            for group in &duplicates {
+                println!("{}", group_json(group));
+            }
+        }
+    }
+}
+
 fn main() {
     let args = clap_app!(dupdirfinder =>
         (version: crate_version!())
@@ -205,38 +770,85 @@ fn main() {
         (about: "A duplicate directory finder.")
         (@arg minsize: -m [MINSIZE] default_value("1") validator(validate_byte_size)
          "Minimum file size to consider")
+        (@arg content: --content "Compare files by content instead of name and size")
+        (@arg include: --include [PATTERNS] "Comma-separated globs (*, ?) anchored on the whole path; only matching files are scanned")
+        (@arg exclude: --exclude [PATTERNS] "Comma-separated globs (*, ?) anchored on the whole path; matching files are skipped")
+        (@arg ext: --ext [EXTENSIONS] "Comma-separated list of extensions to restrict the scan to, e.g. png,jpg")
+        (@arg unified: --unified "Compare all roots within a single tree so cross-root duplicates surface")
+        (@arg format: --format [FORMAT] default_value("human") possible_values(&["human", "json", "ndjson"])
+         "Output format: human (default), json or ndjson")
+        (@arg follow: -s --("follow-symlinks") "Follow symbolic links during traversal")
+        (@arg allocated: --("allocated-size") "Use allocated size (st_blocks * 512) instead of apparent size")
         (@arg root: +required +multiple "Root directory or directories to search.")
     ).get_matches();
 
-    let roots = args.values_of("root").unwrap();
+    let roots: Vec<PathBuf> = args.values_of("root").unwrap().map(PathBuf::from).collect();
     let minsize = unbytify::unbytify(args.value_of("minsize").unwrap()).unwrap();
+    let content_mode = args.is_present("content");
+    let unified = args.is_present("unified");
+    let opts = ScanOptions {
+        follow_symlinks: args.is_present("follow"),
+        size_mode: if args.is_present("allocated") { SizeMode::Allocated } else { SizeMode::Apparent },
+    };
+    let format = match args.value_of("format").unwrap() {
+        "json" => OutputFormat::Json,
+        "ndjson" => OutputFormat::Ndjson,
+        _ => OutputFormat::Human,
+    };
+    let filter = Filter::from_args(args.value_of("include"), args.value_of("exclude"), args.value_of("ext"));
 
     // We take care to avoid visiting a single inode twice,
     // which takes care of (false positive) hardlinks.
-    let mut inodes = HashSet::default();
-
-
-    for root in roots {
-        println!("Checking {} directory", root);
-        println!("");
-
-        let mut map = HashMap::new();
-        let root = PathBuf::from(root);
-        crawl_directory(root, &mut map, &mut inodes);
-        let duplicates = list_duplicates(map, minsize);
+    let inodes = Mutex::new(HashSet::default());
+
+    // Seconde du mur d'horloge au démarrage du scan : sert à repérer les mtime ambiguës.
+    let scan_start_sec = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let cache = HashCache::load(scan_start_sec, content_mode);
+
+
+    if unified {
+        // Toutes les racines alimentent une seule map : un répertoire dupliqué d'une
+        // racine à l'autre est alors détecté par une unique passe de list_duplicates.
+        if format.is_human() {
+            for root in &roots {
+                println!("Checking {} directory", root.display());
+            }
+            println!();
+        }
 
-        for duplicate in duplicates {
-            println!("Duplicat de {:?} répertoires", duplicate.len());
-            let duplicate_size = duplicate.get(0).expect("yet another expectation not met").disk_size;
-            let space_wasted = (duplicate.len() - 1 ) as u64 * duplicate_size;
-            let (val, suffix) = unbytify::bytify(space_wasted);
-            println!("    Space wasted {:.1} {}", val, suffix);
-            for dir in duplicate {
-                println!("{:?}", dir.path);
+        let mode = build_mode(&roots, content_mode, &filter, &opts);
+        let map = Mutex::new(HashMap::new());
+        for root in &roots {
+            crawl_directory(root.clone(), &map, &inodes, &mode, &cache, &filter, &opts);
+        }
+        let duplicates = list_duplicates(map.into_inner().unwrap(), minsize);
+        print_duplicates(duplicates, format);
+        if format.is_human() {
+            println!();
+        }
+    } else {
+        for root in &roots {
+            if format.is_human() {
+                println!("Checking {} directory", root.display());
+                println!();
             }
 
-            println!("");
+            // En mode contenu, une première passe recense les fichiers par taille et
+            // n'en lit les octets qu'en cas de collision de taille.
+            let mode = build_mode(std::slice::from_ref(root), content_mode, &filter, &opts);
+            let map = Mutex::new(HashMap::new());
+            crawl_directory(root.clone(), &map, &inodes, &mode, &cache, &filter, &opts);
+            let duplicates = list_duplicates(map.into_inner().unwrap(), minsize);
+            print_duplicates(duplicates, format);
+            if format.is_human() {
+                println!();
+            }
         }
-        println!("");
     }
+
+    // Persiste le cache mis à jour en fin de scan.
+    cache.save();
 }